@@ -0,0 +1,83 @@
+use std::sync::mpsc;
+
+/// A square-wave beeper driven by the machine's sound timer. `Machine`
+/// sends `true`/`false` over the channel whenever the timer transitions
+/// to/from nonzero; this side turns that into a stream of samples from
+/// the data callback of the output stream that the main thread opens.
+///
+/// The output stream should stay closed until the first `true` arrives
+/// so we don't open an audio device for a ROM that never beeps, and the
+/// generated square wave is passed through a low-pass/high-pass filter
+/// pair to round off the on/off edges, which otherwise ring audibly.
+pub struct Beeper {
+  on: mpsc::Receiver<bool>,
+  active: bool,
+  started: bool,
+  phase: f32,
+  sample_rate: f32,
+  lowpass: f32,
+  highpass_in: f32,
+  highpass_out: f32,
+  pub frequency: f32,
+  pub volume: f32,
+}
+
+impl Beeper {
+  const DEFAULT_FREQUENCY: f32 = 440.0;
+  const DEFAULT_VOLUME: f32 = 0.25;
+  const LOWPASS_ALPHA: f32 = 0.2;
+  const HIGHPASS_ALPHA: f32 = 0.995;
+
+  pub fn new(on: mpsc::Receiver<bool>, sample_rate: f32) -> Self {
+    Beeper {
+      on,
+      active: false,
+      started: false,
+      phase: 0.0,
+      sample_rate,
+      lowpass: 0.0,
+      highpass_in: 0.0,
+      highpass_out: 0.0,
+      frequency: Beeper::DEFAULT_FREQUENCY,
+      volume: Beeper::DEFAULT_VOLUME,
+    }
+  }
+
+  /// Drains pending on/off messages from the CPU thread. Returns true
+  /// once the stream has been asked to play for the first time, so the
+  /// caller knows it's safe to open the output device.
+  pub fn poll(&mut self) -> bool {
+    while let Ok(active) = self.on.try_recv() {
+      self.active = active;
+      self.started |= active;
+    }
+    self.started
+  }
+
+  /// Fills `out` with the next block of samples (silence if the sound
+  /// timer isn't currently active).
+  pub fn fill(&mut self, out: &mut [f32]) {
+    self.poll();
+
+    for sample in out.iter_mut() {
+      let raw = if self.active {
+        self.phase = (self.phase + self.frequency / self.sample_rate) % 1.0;
+        if self.phase < 0.5 {
+          self.volume
+        } else {
+          -self.volume
+        }
+      } else {
+        0.0
+      };
+
+      self.lowpass += Beeper::LOWPASS_ALPHA * (raw - self.lowpass);
+      let highpass =
+        self.lowpass - self.highpass_in + Beeper::HIGHPASS_ALPHA * self.highpass_out;
+      self.highpass_in = self.lowpass;
+      self.highpass_out = highpass;
+
+      *sample = highpass;
+    }
+  }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::instruction;
+use crate::machine::Machine;
+
+enum Mode {
+  Pause,
+  Step(u32),
+  Run,
+}
+
+/// A breakpoint/single-step debugger for `Machine`, driven by commands
+/// typed on stdin. `Machine::run` calls `before_step` once per
+/// instruction, which blocks the CPU thread on the command prompt while
+/// paused so the display thread can keep rendering.
+pub struct Debugger {
+  breakpoints: HashSet<u16>,
+  trace: bool,
+  mode: Mode,
+  last_command: Option<String>,
+}
+
+impl Default for Debugger {
+  fn default() -> Self {
+    Debugger::new()
+  }
+}
+
+impl Debugger {
+  pub fn new() -> Self {
+    Debugger {
+      breakpoints: HashSet::new(),
+      trace: false,
+      mode: Mode::Pause,
+      last_command: None,
+    }
+  }
+
+  pub fn before_step(&mut self, machine: &Machine) {
+    if let Mode::Run = self.mode {
+      if self.breakpoints.contains(&machine.ip()) {
+        self.mode = Mode::Pause;
+        println!("breakpoint hit at {:#06x}", machine.ip());
+      }
+    }
+
+    while let Mode::Pause = self.mode {
+      self.prompt(machine);
+    }
+
+    if self.trace {
+      match machine.peek() {
+        Ok(instr) => println!("{:#06x}: {}", machine.ip(), instr),
+        Err(e) => println!("{:#06x}: {}", machine.ip(), e),
+      }
+    }
+
+    if let Mode::Step(remaining) = self.mode {
+      self.mode = if remaining <= 1 {
+        Mode::Pause
+      } else {
+        Mode::Step(remaining - 1)
+      };
+    }
+  }
+
+  fn prompt(&mut self, machine: &Machine) {
+    print!("({:#06x}) > ", machine.ip());
+    io::stdout().flush().ok();
+
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+      // stdin closed; keep running rather than spin forever.
+      self.mode = Mode::Run;
+      return;
+    }
+
+    let line = line.trim();
+    let command = if line.is_empty() {
+      self.last_command.clone().unwrap_or_default()
+    } else {
+      self.last_command = Some(line.to_string());
+      line.to_string()
+    };
+
+    self.run_command(&command, machine);
+  }
+
+  fn run_command(&mut self, command: &str, machine: &Machine) {
+    let mut parts = command.split_whitespace();
+    match parts.next() {
+      Some("c") | Some("continue") => self.mode = Mode::Run,
+      Some("s") | Some("step") => {
+        let count = parts.next().and_then(|n| n.parse().ok()).unwrap_or(1);
+        self.mode = Mode::Step(count);
+      }
+      Some("b") | Some("break") => match parts.next().and_then(parse_addr) {
+        Some(addr) => {
+          self.breakpoints.insert(addr);
+          println!("breakpoint set at {:#06x}", addr);
+        }
+        None => println!("usage: break <addr>"),
+      },
+      Some("clear") => match parts.next().and_then(parse_addr) {
+        Some(addr) => {
+          self.breakpoints.remove(&addr);
+          println!("breakpoint cleared at {:#06x}", addr);
+        }
+        None => println!("usage: clear <addr>"),
+      },
+      Some("trace") => {
+        self.trace = !self.trace;
+        println!("trace {}", if self.trace { "on" } else { "off" });
+      }
+      Some("regs") => {
+        for (i, v) in machine.registers().iter().enumerate() {
+          println!("v{:x} = {:#04x}", i, v);
+        }
+        println!("i  = {:#06x}", machine.reg_i());
+      }
+      Some("stack") => {
+        for (depth, frame) in machine.stack().iter().enumerate() {
+          println!("#{} {:#06x}", depth, frame);
+        }
+      }
+      Some("mem") => {
+        let addr = parts.next().and_then(parse_addr).unwrap_or(0);
+        let len = parts.next().and_then(|n| n.parse().ok()).unwrap_or(16u16);
+        let memory = machine.memory();
+        if addr as usize >= memory.len() {
+          println!("address {:#06x} is out of bounds", addr);
+          return;
+        }
+        let len = len.min((memory.len() - addr as usize) as u16);
+        for offset in 0..len {
+          if offset % 16 == 0 {
+            if offset > 0 {
+              println!();
+            }
+            print!("{:#06x}:", addr + offset);
+          }
+          print!(" {:02x}", memory[(addr + offset) as usize]);
+        }
+        println!();
+      }
+      Some("list") | Some("disasm") => {
+        let count = parts.next().and_then(|n| n.parse().ok()).unwrap_or(10usize);
+        let rom = &machine.memory()[Machine::LOAD_ADDR..];
+        for (addr, instr) in instruction::disassemble(rom)
+          .into_iter()
+          .skip_while(|(addr, _)| *addr < machine.ip())
+          .take(count)
+        {
+          println!("{:#06x}: {}", addr, instr);
+        }
+      }
+      _ => println!(
+        "commands: step [n], continue, break <addr>, clear <addr>, trace, regs, mem <addr> [len], stack, list [n]"
+      ),
+    }
+  }
+}
+
+fn parse_addr(s: &str) -> Option<u16> {
+  let s = s.trim_start_matches("0x");
+  u16::from_str_radix(s, 16).ok()
+}
@@ -1,7 +1,7 @@
 use std::error::Error;
 use std::fmt::Display;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
   CLS,
   RET,
@@ -33,6 +33,8 @@ pub enum Instruction {
   SDTr { r: u8 },
   SSTr { r: u8 },
   ADDI { r: u8 },
+  LDF { r: u8 },
+  BCD { r: u8 },
   STOR { r: u8 },
   LOAD { r: u8 },
 }
@@ -40,6 +42,7 @@ pub enum Instruction {
 #[derive(Debug)]
 pub enum ParseError {
   InvalidInstruction(u16),
+  InvalidMnemonic(String),
 }
 
 impl Error for ParseError {}
@@ -94,10 +97,311 @@ impl TryFrom<u16> for Instruction {
       [0xF, r, 0x1, 0x5] => Ok(Instruction::SDTr { r }),
       [0xF, r, 0x1, 0x8] => Ok(Instruction::SSTr { r }),
       [0xF, r, 0x1, 0xE] => Ok(Instruction::ADDI { r }),
-      // missing digits
+      [0xF, r, 0x2, 0x9] => Ok(Instruction::LDF { r }),
+      [0xF, r, 0x3, 0x3] => Ok(Instruction::BCD { r }),
       [0xF, r, 0x5, 0x5] => Ok(Instruction::STOR { r }),
       [0xF, r, 0x6, 0x5] => Ok(Instruction::LOAD { r }),
       [_, _, _, _] => Err(ParseError::InvalidInstruction(instr)),
     }
   }
 }
+
+impl From<Instruction> for u16 {
+  fn from(instr: Instruction) -> u16 {
+    match instr {
+      Instruction::CLS => 0x00E0,
+      Instruction::RET => 0x00EE,
+      Instruction::JP { a } => 0x1000 | a,
+      Instruction::CALL { a } => 0x2000 | a,
+      Instruction::SEi { r, v } => 0x3000 | (r as u16) << 8 | v as u16,
+      Instruction::SNEi { r, v } => 0x4000 | (r as u16) << 8 | v as u16,
+      Instruction::SEr { r1, r2 } => 0x5000 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::SETi { r, v } => 0x6000 | (r as u16) << 8 | v as u16,
+      Instruction::ADDi { r, v } => 0x7000 | (r as u16) << 8 | v as u16,
+      Instruction::SETr { r1, r2 } => 0x8000 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::OR { r1, r2 } => 0x8001 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::AND { r1, r2 } => 0x8002 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::XOR { r1, r2 } => 0x8003 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::ADD { r1, r2 } => 0x8004 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::SUB { r1, r2 } => 0x8005 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::SHR { r1, r2 } => 0x8006 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::SUBN { r1, r2 } => 0x8007 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::SHL { r1, r2 } => 0x800E | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::SNEr { r1, r2 } => 0x9000 | (r1 as u16) << 8 | (r2 as u16) << 4,
+      Instruction::LDI { a } => 0xA000 | a,
+      Instruction::JPR { a } => 0xB000 | a,
+      Instruction::RND { r, v } => 0xC000 | (r as u16) << 8 | v as u16,
+      Instruction::DRW { r1, r2, v } => 0xD000 | (r1 as u16) << 8 | (r2 as u16) << 4 | v as u16,
+      Instruction::SKP { v } => 0xE09E | (v as u16) << 8,
+      Instruction::SKNP { v } => 0xE0A1 | (v as u16) << 8,
+      Instruction::LDT { r } => 0xF007 | (r as u16) << 8,
+      Instruction::INP { r } => 0xF00A | (r as u16) << 8,
+      Instruction::SDTr { r } => 0xF015 | (r as u16) << 8,
+      Instruction::SSTr { r } => 0xF018 | (r as u16) << 8,
+      Instruction::ADDI { r } => 0xF01E | (r as u16) << 8,
+      Instruction::LDF { r } => 0xF029 | (r as u16) << 8,
+      Instruction::BCD { r } => 0xF033 | (r as u16) << 8,
+      Instruction::STOR { r } => 0xF055 | (r as u16) << 8,
+      Instruction::LOAD { r } => 0xF065 | (r as u16) << 8,
+    }
+  }
+}
+
+impl Display for Instruction {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match *self {
+      Instruction::CLS => write!(f, "CLS"),
+      Instruction::RET => write!(f, "RET"),
+      Instruction::JP { a } => write!(f, "JP {:#05x}", a),
+      Instruction::CALL { a } => write!(f, "CALL {:#05x}", a),
+      Instruction::SEi { r, v } => write!(f, "SE V{:X}, {:#04x}", r, v),
+      Instruction::SNEi { r, v } => write!(f, "SNE V{:X}, {:#04x}", r, v),
+      Instruction::SEr { r1, r2 } => write!(f, "SE V{:X}, V{:X}", r1, r2),
+      Instruction::SETi { r, v } => write!(f, "LD V{:X}, {:#04x}", r, v),
+      Instruction::ADDi { r, v } => write!(f, "ADD V{:X}, {:#04x}", r, v),
+      Instruction::SETr { r1, r2 } => write!(f, "LD V{:X}, V{:X}", r1, r2),
+      Instruction::OR { r1, r2 } => write!(f, "OR V{:X}, V{:X}", r1, r2),
+      Instruction::AND { r1, r2 } => write!(f, "AND V{:X}, V{:X}", r1, r2),
+      Instruction::XOR { r1, r2 } => write!(f, "XOR V{:X}, V{:X}", r1, r2),
+      Instruction::ADD { r1, r2 } => write!(f, "ADD V{:X}, V{:X}", r1, r2),
+      Instruction::SUB { r1, r2 } => write!(f, "SUB V{:X}, V{:X}", r1, r2),
+      Instruction::SHR { r1, r2 } => write!(f, "SHR V{:X}, V{:X}", r1, r2),
+      Instruction::SUBN { r1, r2 } => write!(f, "SUBN V{:X}, V{:X}", r1, r2),
+      Instruction::SHL { r1, r2 } => write!(f, "SHL V{:X}, V{:X}", r1, r2),
+      Instruction::SNEr { r1, r2 } => write!(f, "SNE V{:X}, V{:X}", r1, r2),
+      Instruction::LDI { a } => write!(f, "LD I, {:#05x}", a),
+      Instruction::JPR { a } => write!(f, "JP V0, {:#05x}", a),
+      Instruction::RND { r, v } => write!(f, "RND V{:X}, {:#04x}", r, v),
+      Instruction::DRW { r1, r2, v } => write!(f, "DRW V{:X}, V{:X}, {}", r1, r2, v),
+      Instruction::SKP { v } => write!(f, "SKP V{:X}", v),
+      Instruction::SKNP { v } => write!(f, "SKNP V{:X}", v),
+      Instruction::LDT { r } => write!(f, "LD V{:X}, DT", r),
+      Instruction::INP { r } => write!(f, "LD V{:X}, K", r),
+      Instruction::SDTr { r } => write!(f, "LD DT, V{:X}", r),
+      Instruction::SSTr { r } => write!(f, "LD ST, V{:X}", r),
+      Instruction::ADDI { r } => write!(f, "ADD I, V{:X}", r),
+      Instruction::LDF { r } => write!(f, "LD F, V{:X}", r),
+      Instruction::BCD { r } => write!(f, "LD B, V{:X}", r),
+      Instruction::STOR { r } => write!(f, "LD [I], V{:X}", r),
+      Instruction::LOAD { r } => write!(f, "LD V{:X}, [I]", r),
+    }
+  }
+}
+
+fn parse_reg(tok: &str) -> Result<u8, ParseError> {
+  let tok = tok.trim();
+  if tok.len() > 1 && (tok.starts_with('V') || tok.starts_with('v')) {
+    u8::from_str_radix(&tok[1..], 16).map_err(|_| ParseError::InvalidMnemonic(tok.to_string()))
+  } else {
+    Err(ParseError::InvalidMnemonic(tok.to_string()))
+  }
+}
+
+fn parse_num(tok: &str) -> Result<u16, ParseError> {
+  let tok = tok.trim();
+  match tok.strip_prefix("0x").or_else(|| tok.strip_prefix("0X")) {
+    Some(hex) => u16::from_str_radix(hex, 16).map_err(|_| ParseError::InvalidMnemonic(tok.to_string())),
+    None => tok.parse().map_err(|_| ParseError::InvalidMnemonic(tok.to_string())),
+  }
+}
+
+fn parse_ld(dst: &str, src: &str) -> Result<Instruction, ParseError> {
+  if dst.eq_ignore_ascii_case("I") {
+    return Ok(Instruction::LDI { a: parse_num(src)? });
+  }
+  if dst.eq_ignore_ascii_case("DT") {
+    return Ok(Instruction::SDTr { r: parse_reg(src)? });
+  }
+  if dst.eq_ignore_ascii_case("ST") {
+    return Ok(Instruction::SSTr { r: parse_reg(src)? });
+  }
+  if dst.eq_ignore_ascii_case("F") {
+    return Ok(Instruction::LDF { r: parse_reg(src)? });
+  }
+  if dst.eq_ignore_ascii_case("B") {
+    return Ok(Instruction::BCD { r: parse_reg(src)? });
+  }
+  if dst.eq_ignore_ascii_case("[I]") {
+    return Ok(Instruction::STOR { r: parse_reg(src)? });
+  }
+
+  let r = parse_reg(dst)?;
+  if src.eq_ignore_ascii_case("DT") {
+    Ok(Instruction::LDT { r })
+  } else if src.eq_ignore_ascii_case("K") {
+    Ok(Instruction::INP { r })
+  } else if src.eq_ignore_ascii_case("[I]") {
+    Ok(Instruction::LOAD { r })
+  } else if let Ok(r2) = parse_reg(src) {
+    Ok(Instruction::SETr { r1: r, r2 })
+  } else {
+    Ok(Instruction::SETi { r, v: parse_num(src)? as u8 })
+  }
+}
+
+/// Parses one line of conventional CHIP-8 assembly (the inverse of
+/// `Display`) into an `Instruction`, e.g. `"DRW V1, V2, 5"` or
+/// `"LD I, 0x250"`. A trailing `; comment` is ignored.
+pub fn parse(line: &str) -> Result<Instruction, ParseError> {
+  let line = line.split(';').next().unwrap_or("").trim();
+  if line.is_empty() {
+    return Err(ParseError::InvalidMnemonic(String::new()));
+  }
+
+  let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+  let operands: Vec<&str> = if rest.is_empty() {
+    vec![]
+  } else {
+    rest.split(',').map(str::trim).collect()
+  };
+  let op = |i: usize| operands.get(i).copied().unwrap_or("");
+
+  match mnemonic.to_uppercase().as_str() {
+    "CLS" => Ok(Instruction::CLS),
+    "RET" => Ok(Instruction::RET),
+    "CALL" => Ok(Instruction::CALL { a: parse_num(op(0))? }),
+    "JP" => {
+      if operands.len() == 2 {
+        Ok(Instruction::JPR { a: parse_num(op(1))? })
+      } else {
+        Ok(Instruction::JP { a: parse_num(op(0))? })
+      }
+    }
+    "SE" => {
+      let r = parse_reg(op(0))?;
+      match parse_reg(op(1)) {
+        Ok(r2) => Ok(Instruction::SEr { r1: r, r2 }),
+        Err(_) => Ok(Instruction::SEi { r, v: parse_num(op(1))? as u8 }),
+      }
+    }
+    "SNE" => {
+      let r = parse_reg(op(0))?;
+      match parse_reg(op(1)) {
+        Ok(r2) => Ok(Instruction::SNEr { r1: r, r2 }),
+        Err(_) => Ok(Instruction::SNEi { r, v: parse_num(op(1))? as u8 }),
+      }
+    }
+    "LD" => parse_ld(op(0), op(1)),
+    "ADD" => {
+      if op(0).eq_ignore_ascii_case("I") {
+        Ok(Instruction::ADDI { r: parse_reg(op(1))? })
+      } else {
+        let r1 = parse_reg(op(0))?;
+        match parse_reg(op(1)) {
+          Ok(r2) => Ok(Instruction::ADD { r1, r2 }),
+          Err(_) => Ok(Instruction::ADDi { r: r1, v: parse_num(op(1))? as u8 }),
+        }
+      }
+    }
+    "OR" => Ok(Instruction::OR { r1: parse_reg(op(0))?, r2: parse_reg(op(1))? }),
+    "AND" => Ok(Instruction::AND { r1: parse_reg(op(0))?, r2: parse_reg(op(1))? }),
+    "XOR" => Ok(Instruction::XOR { r1: parse_reg(op(0))?, r2: parse_reg(op(1))? }),
+    "SUB" => Ok(Instruction::SUB { r1: parse_reg(op(0))?, r2: parse_reg(op(1))? }),
+    "SUBN" => Ok(Instruction::SUBN { r1: parse_reg(op(0))?, r2: parse_reg(op(1))? }),
+    "SHR" => Ok(Instruction::SHR {
+      r1: parse_reg(op(0))?,
+      r2: parse_reg(op(1)).unwrap_or(0),
+    }),
+    "SHL" => Ok(Instruction::SHL {
+      r1: parse_reg(op(0))?,
+      r2: parse_reg(op(1)).unwrap_or(0),
+    }),
+    "RND" => Ok(Instruction::RND { r: parse_reg(op(0))?, v: parse_num(op(1))? as u8 }),
+    "DRW" => Ok(Instruction::DRW {
+      r1: parse_reg(op(0))?,
+      r2: parse_reg(op(1))?,
+      v: parse_num(op(2))? as u8,
+    }),
+    "SKP" => Ok(Instruction::SKP { v: parse_reg(op(0))? }),
+    "SKNP" => Ok(Instruction::SKNP { v: parse_reg(op(0))? }),
+    _ => Err(ParseError::InvalidMnemonic(line.to_string())),
+  }
+}
+
+/// Assembles a full `.ch8` source listing (one instruction per line) into
+/// a ROM image, the inverse of `disassemble`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, ParseError> {
+  let mut out = vec![];
+
+  for line in source.lines() {
+    let line = line.split(';').next().unwrap_or("").trim();
+    if line.is_empty() {
+      continue;
+    }
+
+    let word: u16 = parse(line)?.into();
+    out.extend_from_slice(&word.to_be_bytes());
+  }
+
+  Ok(out)
+}
+
+/// Walks a ROM image two bytes at a time, pairing each load address with
+/// its decoded instruction. Used by the debugger's listing view. Words
+/// that don't decode to a known instruction (e.g. raw sprite data mixed
+/// into the ROM) are skipped rather than aborting the whole listing.
+pub fn disassemble(rom: &[u8]) -> Vec<(u16, Instruction)> {
+  const BASE: u16 = 0x200;
+  let mut out = vec![];
+
+  for (i, chunk) in rom.chunks_exact(2).enumerate() {
+    let word = (chunk[0] as u16) << 8 | chunk[1] as u16;
+    if let Ok(instr) = Instruction::try_from(word) {
+      out.push((BASE + (i as u16) * 2, instr));
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_encode_round_trips_for_every_opcode() {
+    for word in 0..=u16::MAX {
+      if let Ok(instr) = Instruction::try_from(word) {
+        assert_eq!(u16::from(instr), word, "opcode {:#06x} round-tripped wrong", word);
+      }
+    }
+  }
+
+  #[test]
+  fn parse_display_round_trips_for_every_opcode() {
+    for word in 0..=u16::MAX {
+      let Ok(instr) = Instruction::try_from(word) else {
+        continue;
+      };
+
+      let text = instr.to_string();
+      let reparsed = parse(&text).unwrap_or_else(|e| panic!("failed to parse {:?}: {:?}", text, e));
+      assert_eq!(
+        u16::from(reparsed),
+        word,
+        "{:?} parsed back to a different opcode",
+        text
+      );
+    }
+  }
+
+  #[test]
+  fn assemble_disassemble_round_trips() {
+    let source = "\
+      CLS\n\
+      LD V0, 0x05\n\
+      LD V1, 0x0A\n\
+      ADD V0, V1\n\
+      DRW V0, V1, 5\n\
+      JP 0x200\n";
+
+    let rom = assemble(source).expect("assemble");
+    let listing = disassemble(&rom);
+
+    assert_eq!(listing.len(), 6);
+    assert_eq!(listing[0].0, 0x200);
+    assert_eq!(listing[1].0, 0x202);
+    assert!(matches!(listing[0].1, Instruction::CLS));
+    assert!(matches!(listing[5].1, Instruction::JP { a: 0x200 }));
+  }
+}
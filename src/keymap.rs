@@ -0,0 +1,39 @@
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Maps physical keys to the 16 CHIP-8 keypad keys (0x0-0xF). The default
+/// layout is the conventional 1234/QWER/ASDF/ZXCV grid, which lines up
+/// with the original COSMAC VIP's 4x4 hex keypad:
+///
+/// ```text
+/// 1 2 3 4        1 2 3 C
+/// Q W E R   ->   4 5 6 D
+/// A S D F        7 8 9 E
+/// Z X C V        A 0 B F
+/// ```
+pub struct Keymap {
+  keys: [VirtualKeyCode; 16],
+}
+
+impl Keymap {
+  pub fn standard() -> Self {
+    use VirtualKeyCode::*;
+    Keymap {
+      keys: [
+        X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V,
+      ],
+    }
+  }
+
+  /// Builds the CHIP-8 keypad bitmask (bit N set if key N is held) from
+  /// the current frame's input state.
+  pub fn bitmask(&self, input: &WinitInputHelper) -> u16 {
+    let mut mask = 0u16;
+    for (key, code) in self.keys.iter().enumerate() {
+      if input.key_held(*code) {
+        mask |= 1 << key;
+      }
+    }
+    mask
+  }
+}
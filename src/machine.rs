@@ -4,9 +4,18 @@ use std::time::{Duration, Instant};
 
 use fastrand;
 
+use crate::debugger::Debugger;
 use crate::instruction::{Instruction, ParseError};
+use crate::quirks::Quirks;
 use crate::screen::ScreenUpdate;
 
+/// Requests sent to a running `Machine` from another thread, e.g. the
+/// winit event loop handling save-state key bindings.
+pub enum ControlMessage {
+  Save(mpsc::Sender<Vec<u8>>),
+  Load(Vec<u8>),
+}
+
 pub struct Machine {
   memory: [u8; Machine::MEMORY_SIZE],
   registers: [u8; Machine::REGISTER_COUNT],
@@ -17,25 +26,53 @@ pub struct Machine {
   display: mpsc::Sender<ScreenUpdate>,
   collision: mpsc::Receiver<bool>,
   keypad: mpsc::Receiver<u16>,
+  audio: mpsc::Sender<bool>,
+  control: mpsc::Receiver<ControlMessage>,
   keys: u16,
+  quirks: Quirks,
 }
 
 impl Machine {
   const MEMORY_SIZE: usize = 0x1000;
   const REGISTER_COUNT: usize = 16;
   const TIMER_COUNT: usize = 2;
-  const LOAD_ADDR: usize = 0x200;
+  pub(crate) const LOAD_ADDR: usize = 0x200;
+  const FONT_ADDR: usize = 0x000;
+  const FONT_CHAR_SIZE: u16 = 5;
+
+  // Conventional CHIP-8 hex font: 16 glyphs (0-F), five bytes each.
+  const FONT_SET: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+  ];
 
   pub fn load(
     input: &[u8],
     display: mpsc::Sender<ScreenUpdate>,
     collision: mpsc::Receiver<bool>,
     keypad: mpsc::Receiver<u16>,
+    audio: mpsc::Sender<bool>,
+    control: mpsc::Receiver<ControlMessage>,
+    quirks: Quirks,
   ) -> Result<Self, ()> {
     if input.len() > 0x1000 - Machine::LOAD_ADDR {
       Err(())
     } else {
-      let mut m = Machine::new(display, collision, keypad);
+      let mut m = Machine::new(display, collision, keypad, audio, control, quirks);
       m.memory[Machine::LOAD_ADDR..][..input.len()].copy_from_slice(&input);
       Ok(m)
     }
@@ -45,9 +82,15 @@ impl Machine {
     display: mpsc::Sender<ScreenUpdate>,
     collision: mpsc::Receiver<bool>,
     keypad: mpsc::Receiver<u16>,
+    audio: mpsc::Sender<bool>,
+    control: mpsc::Receiver<ControlMessage>,
+    quirks: Quirks,
   ) -> Self {
+    let mut memory = [0; Machine::MEMORY_SIZE];
+    memory[Machine::FONT_ADDR..][..Machine::FONT_SET.len()].copy_from_slice(&Machine::FONT_SET);
+
     Machine {
-      memory: [0; Machine::MEMORY_SIZE],
+      memory,
       registers: [0; 16],
       reg_i: 0,
       timers: [0; 2],
@@ -57,15 +100,34 @@ impl Machine {
       display,
       collision,
       keypad,
+      audio,
+      control,
+      quirks,
     }
   }
 
-  pub fn run(&mut self, hz: u32) -> Result<(), ParseError> {
+  const TIMER_HZ: f64 = 60.0;
+
+  pub fn run(&mut self, hz: u32, mut debugger: Option<Debugger>) -> Result<(), ParseError> {
     let interval_ms = Duration::from_secs_f64(1.0 / (hz as f64));
+    let timer_interval = Duration::from_secs_f64(1.0 / Machine::TIMER_HZ);
     let mut last = Instant::now();
+    let mut last_timer_tick = last;
 
     loop {
+      if let Some(debugger) = debugger.as_mut() {
+        debugger.before_step(self);
+      }
+
       self.step()?;
+
+      let since_timer_tick = Instant::now() - last_timer_tick;
+      let ticks = (since_timer_tick.as_secs_f64() / timer_interval.as_secs_f64()) as u32;
+      if ticks > 0 {
+        self.decrement_timers(ticks);
+        last_timer_tick += timer_interval * ticks;
+      }
+
       // TODO: improve timing logic
       let mut remaining = interval_ms.saturating_sub(Instant::now() - last);
       while !remaining.is_zero() {
@@ -92,11 +154,38 @@ impl Machine {
     self.memory[a as usize] = v;
   }
 
+  fn decode_at(&self, addr: u16) -> Result<Instruction, ParseError> {
+    let hi = self.memory[addr as usize];
+    let lo = self.memory[(addr + 1) as usize];
+    ((hi as u16) << 8 | (lo as u16)).try_into()
+  }
+
+  pub(crate) fn ip(&self) -> u16 {
+    self.ip
+  }
+
+  pub(crate) fn reg_i(&self) -> u16 {
+    self.reg_i
+  }
+
+  pub(crate) fn registers(&self) -> &[u8; Machine::REGISTER_COUNT] {
+    &self.registers
+  }
+
+  pub(crate) fn stack(&self) -> &[u16] {
+    &self.stack
+  }
+
+  pub(crate) fn memory(&self) -> &[u8] {
+    &self.memory
+  }
+
+  pub(crate) fn peek(&self) -> Result<Instruction, ParseError> {
+    self.decode_at(self.ip)
+  }
+
   fn eval_next(&mut self) -> Result<(), ParseError> {
-    let hi = self.memory[self.ip as usize];
-    let lo = self.memory[(self.ip + 1) as usize];
-    let instr: Instruction = ((hi as u16) << 8 | (lo as u16)).try_into()?;
-    //println!("{:?}: {:#02x}", instr, self.keys);
+    let instr = self.decode_at(self.ip)?;
     let next_instr = self.ip + 2;
 
     self.ip = match instr {
@@ -171,10 +260,14 @@ impl Machine {
         self.reg_set(0xf, if carry { 1 } else { 0 });
         next_instr
       }
-      Instruction::SHR { r1, .. } => {
-        let (v, carry) = self.reg(r1).overflowing_shr(1);
-        self.reg_set(r1, v);
-        self.reg_set(0xf, if carry { 1 } else { 0 });
+      Instruction::SHR { r1, r2 } => {
+        let v = if self.quirks.shift_copies_vy {
+          self.reg(r2)
+        } else {
+          self.reg(r1)
+        };
+        self.reg_set(r1, v >> 1);
+        self.reg_set(0xf, v & 0x1);
         next_instr
       }
       Instruction::SUBN { r1, r2 } => {
@@ -183,10 +276,14 @@ impl Machine {
         self.reg_set(0xf, if carry { 1 } else { 0 });
         next_instr
       }
-      Instruction::SHL { r1, .. } => {
-        let (v, carry) = self.reg(r1).overflowing_shl(1);
-        self.reg_set(r1, v);
-        self.reg_set(0xf, if carry { 1 } else { 0 });
+      Instruction::SHL { r1, r2 } => {
+        let v = if self.quirks.shift_copies_vy {
+          self.reg(r2)
+        } else {
+          self.reg(r1)
+        };
+        self.reg_set(r1, v << 1);
+        self.reg_set(0xf, (v >> 7) & 0x1);
         next_instr
       }
       Instruction::SNEr { r1, r2 } => {
@@ -200,7 +297,14 @@ impl Machine {
         self.reg_i = a;
         next_instr
       }
-      Instruction::JPR { a } => a + self.reg(0) as u16,
+      Instruction::JPR { a } => {
+        let base = if self.quirks.jump_offset_uses_vx {
+          self.reg(((a >> 8) & 0xF) as u8)
+        } else {
+          self.reg(0)
+        };
+        a + base as u16
+      }
       Instruction::RND { r, v } => {
         self.reg_set(r, fastrand::u8(..) & v);
         next_instr
@@ -226,18 +330,14 @@ impl Machine {
         next_instr
       }
       Instruction::SKP { v } => {
-        println!("{:?}", instr);
-        println!("{:?}", self.keys);
-        if self.keys == v as u16 {
+        if self.keys & (1 << (self.reg(v) & 0xF)) != 0 {
           next_instr + 2
         } else {
           next_instr
         }
       }
       Instruction::SKNP { v } => {
-        println!("{:?}", instr);
-        println!("{:?}", self.keys);
-        if self.keys != v as u16 {
+        if self.keys & (1 << (self.reg(v) & 0xF)) == 0 {
           next_instr + 2
         } else {
           next_instr
@@ -248,8 +348,16 @@ impl Machine {
         next_instr
       }
       Instruction::INP { r } => {
-        println!("unhandled instruction {:?}", instr);
-        // TODO: block for input
+        // Block until a key-down arrives rather than busy-spinning the
+        // channel; `step` only polls `keypad` with `try_recv` otherwise.
+        loop {
+          let keys = self.keypad.recv().expect("Keypad disconnected");
+          self.keys = keys;
+          if keys != 0 {
+            self.reg_set(r, keys.trailing_zeros() as u8);
+            break;
+          }
+        }
         next_instr
       }
       Instruction::SDTr { r } => {
@@ -257,23 +365,44 @@ impl Machine {
         next_instr
       }
       Instruction::SSTr { r } => {
-        self.timers[1] = self.reg(r);
+        let v = self.reg(r);
+        if (self.timers[1] == 0) != (v == 0) {
+          self.audio.send(v != 0).expect("Audio disconnected");
+        }
+        self.timers[1] = v;
         next_instr
       }
       Instruction::ADDI { r } => {
         self.reg_i += self.reg(r) as u16;
         next_instr
       }
+      Instruction::LDF { r } => {
+        self.reg_i = Machine::FONT_ADDR as u16 + (self.reg(r) as u16) * Machine::FONT_CHAR_SIZE;
+        next_instr
+      }
+      Instruction::BCD { r } => {
+        let v = self.reg(r);
+        self.mem_set(self.reg_i, v / 100);
+        self.mem_set(self.reg_i + 1, (v / 10) % 10);
+        self.mem_set(self.reg_i + 2, v % 10);
+        next_instr
+      }
       Instruction::STOR { r } => {
         for i in 0..(r + 1) {
           self.mem_set(self.reg_i + i as u16, self.reg(i));
         }
+        if self.quirks.store_load_increments_i {
+          self.reg_i += r as u16 + 1;
+        }
         next_instr
       }
       Instruction::LOAD { r } => {
         for i in 0..(r + 1) {
           self.reg_set(i, self.mem(self.reg_i + i as u16));
         }
+        if self.quirks.store_load_increments_i {
+          self.reg_i += r as u16 + 1;
+        }
         next_instr
       }
     };
@@ -282,16 +411,109 @@ impl Machine {
   }
 
   fn step(&mut self) -> Result<(), ParseError> {
-    ////self.decrement_timers();
     while let Ok(v) = self.keypad.try_recv() {
       self.keys = v;
     }
+
+    while let Ok(msg) = self.control.try_recv() {
+      match msg {
+        ControlMessage::Save(reply) => {
+          reply.send(self.save_state()).ok();
+        }
+        ControlMessage::Load(data) => {
+          if let Err(e) = self.load_state(&data) {
+            eprintln!("Failed to restore save state: {}", e);
+          }
+        }
+      }
+    }
+
     self.eval_next()
   }
 
-  fn decrement_timers(&mut self) {
-    self.timers[0] = self.timers[0].saturating_sub(1);
-    self.timers[1] = self.timers[1].saturating_sub(1);
+  /// Serializes the full CPU-visible state (memory, registers, `I`,
+  /// timers, `ip`, call stack) for a save state. The framebuffer lives on
+  /// the display thread, not here, so callers that also want to restore
+  /// the screen need to snapshot `Screen` separately.
+  pub fn save_state(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(
+      Machine::MEMORY_SIZE + Machine::REGISTER_COUNT + 2 + Machine::TIMER_COUNT + 2 + 2 + self.stack.len() * 2,
+    );
+    out.extend_from_slice(&self.memory);
+    out.extend_from_slice(&self.registers);
+    out.extend_from_slice(&self.reg_i.to_be_bytes());
+    out.extend_from_slice(&self.timers);
+    out.extend_from_slice(&self.ip.to_be_bytes());
+    out.extend_from_slice(&(self.stack.len() as u16).to_be_bytes());
+    for frame in &self.stack {
+      out.extend_from_slice(&frame.to_be_bytes());
+    }
+    out
+  }
+
+  /// Restores state produced by `save_state`. Returns an error instead of
+  /// panicking if `data` is too short for the fixed fields or for the
+  /// stack frames it declares (e.g. a partial write from a crash), since
+  /// this is fed straight from whatever `fs::read` turns up on disk. The
+  /// contents are not otherwise validated, and trailing bytes are ignored.
+  pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+    const FIXED_LEN: usize =
+      Machine::MEMORY_SIZE + Machine::REGISTER_COUNT + 2 + Machine::TIMER_COUNT + 2 + 2;
+
+    if data.len() < FIXED_LEN {
+      return Err(format!(
+        "save state too short: expected at least {} bytes, got {}",
+        FIXED_LEN,
+        data.len()
+      ));
+    }
+
+    let mut cursor = 0;
+
+    self.memory.copy_from_slice(&data[cursor..][..Machine::MEMORY_SIZE]);
+    cursor += Machine::MEMORY_SIZE;
+
+    self.registers.copy_from_slice(&data[cursor..][..Machine::REGISTER_COUNT]);
+    cursor += Machine::REGISTER_COUNT;
+
+    self.reg_i = u16::from_be_bytes([data[cursor], data[cursor + 1]]);
+    cursor += 2;
+
+    self.timers.copy_from_slice(&data[cursor..][..Machine::TIMER_COUNT]);
+    cursor += Machine::TIMER_COUNT;
+
+    self.ip = u16::from_be_bytes([data[cursor], data[cursor + 1]]);
+    cursor += 2;
+
+    let frames = u16::from_be_bytes([data[cursor], data[cursor + 1]]) as usize;
+    cursor += 2;
+
+    if data.len() - cursor < frames * 2 {
+      return Err(format!(
+        "save state truncated: expected {} stack bytes, got {}",
+        frames * 2,
+        data.len() - cursor
+      ));
+    }
+
+    self.stack.clear();
+    for _ in 0..frames {
+      self.stack.push(u16::from_be_bytes([data[cursor], data[cursor + 1]]));
+      cursor += 2;
+    }
+
+    Ok(())
+  }
+
+  fn decrement_timers(&mut self, ticks: u32) {
+    let ticks = ticks.min(u8::MAX as u32) as u8;
+    self.timers[0] = self.timers[0].saturating_sub(ticks);
+
+    let was_active = self.timers[1] > 0;
+    self.timers[1] = self.timers[1].saturating_sub(ticks);
+    if was_active && self.timers[1] == 0 {
+      self.audio.send(false).expect("Audio disconnected");
+    }
   }
 }
 
@@ -1,13 +1,13 @@
 use std::env;
+use std::fs;
 use std::fs::File;
-use std::fmt::Display;
 use std::io::Read;
-use std::time::{Instant, Duration};
+use std::path::PathBuf;
 
-use std::thread;
 use std::sync::mpsc;
+use std::thread;
 
-use fastrand;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use pixels::{Pixels, SurfaceTexture};
 use winit::dpi::LogicalSize;
 use winit::event::{Event, VirtualKeyCode};
@@ -15,279 +15,142 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-struct Machine {
-  memory:    [u8; Machine::MEMORY_SIZE],
-  registers: [u8; Machine::REGISTER_COUNT],
-  reg_i:     u16,
-  timers:    [u8; Machine::TIMER_COUNT],
-  ip:        u16,
-  stack:     Vec<u16>,
-  display:   mpsc::Sender<DisplayUpdate>,
-}
-
-#[derive(Debug)]
-struct DisplayUpdate {
-  bytes: Vec<u8>,
-  coords: (usize, usize)
-}
+mod audio;
+mod debugger;
+mod instruction;
+mod keymap;
+mod machine;
+mod quirks;
+mod save_state;
+mod screen;
 
-type Screen = [[u8; 64]; 32];
-
-impl Display for Machine {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    writeln!(f, "ip = {:#02x}", self.ip)?;
-    for (index, value) in self.registers.iter().enumerate() {
-      writeln!(f, "r{} = {}", index, value)?;
-    };
-
-    for i in (0..Machine::MEMORY_SIZE).step_by(16) {
-      write!(f, "{:#02x}\t", i)?;
-      self.memory[i..(i + 16)].iter().for_each(|value| {
-        write!(f, "{:02x} ", value).unwrap();
-      });
-      write!(f, "\t")?;
-      self.memory[i..(i + 16)].iter().for_each(|&value| {
-        let ch = if value < 0x20 || value > 0x7e {
-          '.'
-        } else {
-          value as char
-        };
-        write!(f, "{}", ch).unwrap();
-      });
-      writeln!(f)?;
-    }
+use audio::Beeper;
+use debugger::Debugger;
+use keymap::Keymap;
+use machine::{ControlMessage, Machine};
+use quirks::Quirks;
+use screen::{Screen, ScreenUpdate};
 
-    Ok(())
+/// Maps the save-state slot keys F1-F9 to a slot number.
+fn slot_for_key(key: VirtualKeyCode) -> Option<u32> {
+  use VirtualKeyCode::*;
+  match key {
+    F1 => Some(1),
+    F2 => Some(2),
+    F3 => Some(3),
+    F4 => Some(4),
+    F5 => Some(5),
+    F6 => Some(6),
+    F7 => Some(7),
+    F8 => Some(8),
+    F9 => Some(9),
+    _ => None,
   }
 }
 
-impl Machine {
-  const MEMORY_SIZE : usize = 0x1000;
-  const REGISTER_COUNT : usize = 16;
-  const TIMER_COUNT : usize = 2;
-  const LOAD_ADDR : usize = 0x200;
-
-  fn load(input: &[u8], display: mpsc::Sender<DisplayUpdate>) -> Result<Self, ()> {
-    if input.len() > 0x1000 - Machine::LOAD_ADDR {
-      Err(())
-    } else {
-      let mut m = Machine::new(display);
-      m.memory[Machine::LOAD_ADDR..][..input.len()].copy_from_slice(&input);
-      Ok(m)
-    }
-  }
+/// Assembles `src_path` (conventional CHIP-8 mnemonics, one per line) into
+/// a `.ch8` ROM image at `out_path`.
+fn asm(src_path: &str, out_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let source = fs::read_to_string(src_path)?;
+  let rom = instruction::assemble(&source)?;
+  fs::write(out_path, rom)?;
+  Ok(())
+}
 
-  fn new(display: mpsc::Sender<DisplayUpdate>) -> Self {
-    Machine {
-      memory:    [0; Machine::MEMORY_SIZE],
-      registers: [0; 16],
-      reg_i:     0,
-      timers:    [0; 2],
-      ip:        Machine::LOAD_ADDR as u16,
-      stack:     vec![],
-      display,
-    }
+/// Prints a disassembly listing of `rom_path` to stdout.
+fn disasm(rom_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let mut buf = vec![];
+  File::open(rom_path)?.read_to_end(&mut buf)?;
+  for (addr, instr) in instruction::disassemble(&buf) {
+    println!("{:#06x}: {}", addr, instr);
   }
+  Ok(())
+}
 
-  fn run(&mut self, hz: u32) {
-    let interval_ms = Duration::from_secs_f64(1.0 / (hz as f64));
-    let mut last = Instant::now();
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+  let args: Vec<String> = env::args().skip(1).collect();
 
-    loop {
-      self.step();
-      let remaining = interval_ms.saturating_sub(Instant::now() - last);
-      if remaining.is_zero() {
-        println!("Warning: unable to maintain CPU hz");
-      }
-      std::thread::sleep(remaining);
-      last = Instant::now();
-    };
+  if let Some(pos) = args.iter().position(|a| a == "--asm") {
+    let src_path = args.get(pos + 1).expect("--asm requires <in.asm> <out.ch8>");
+    let out_path = args.get(pos + 2).expect("--asm requires <in.asm> <out.ch8>");
+    return asm(src_path, out_path);
   }
 
-  fn eval_next(&mut self) {
-    let hi    = self.memory[self.ip as usize];
-    let lo    = self.memory[(self.ip + 1) as usize];
-    let instr = (hi as u16) << 8 | (lo as u16);
-    let rest  = instr & 0x0FFF;
-    let nibbles = [
-      (0xF000 & instr) >> 12,
-      (0x0F00 & instr) >> 8,
-      (0x00F0 & instr) >> 4,
-      (0x000F & instr) >> 0
-    ];
-    println!("{:#02x}", instr);
-    match nibbles {
-      [0x0, 0x0, 0xE, 0x0] => {
-        // CLS
-        self.ip += 2;
-      },
-      // [0x0, 0x0, 0xE, 0xE] => {
-      //   // RET
-      // },
-      [0x1, _, _, _] => {
-        // JP
-        self.ip = rest;
-      },
-      [0x2, _, _, _] => {
-        // CALL
-        self.stack.push(self.ip as u16);
-        self.ip = rest;
-      },
-      [0x3, x, _, _] => {
-        // SE
-        self.ip += if self.registers[x as usize] == lo {
-          4
-        } else {
-          2
-        };
-      },
-      [0x4, x, _, _] => {
-        // SNE
-        self.ip += if self.registers[x as usize] != lo {
-          4
-        } else {
-          2
-        };
-      },
-      [0x5, x, y, 0] => {
-        // SE
-        self.ip += if self.registers[x as usize] == self.registers[y as usize] {
-          4
-        } else {
-          2
-        };
-      },
-      [0x6, x, _, _] => {
-        // LD
-        self.registers[x as usize] = lo;
-        self.ip += 2;
-      },
-      [0x7, x, _, _] => {
-        self.registers[x as usize] += lo;
-        self.ip += 2;
-      },
-      [0x8, x, y, 0] => {
-        self.registers[x as usize] = self.registers[y as usize];
-        self.ip += 2;
-      },
-      [0x8, x, y, 1] => {
-        self.registers[x as usize] |= self.registers[y as usize];
-        self.ip += 2;
-      },
-      [0x8, x, y, 2] => {
-        self.registers[x as usize] &= self.registers[y as usize];
-        self.ip += 2;
-      },
-      [0x8, x, y, 3] => {
-        self.registers[x as usize] ^= self.registers[y as usize];
-        self.ip += 2;
-      },
-      [0x8, x, y, 4] => {
-        let (v, carry) = self.registers[x as usize].overflowing_add(self.registers[y as usize]);
-        self.registers[x as usize] = v;
-        self.registers[0xf] = if carry { 1 } else {  0 };
-        self.ip += 2;
-      },
-      [0x8, x, y, 5] => {
-        let (v, carry) = self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
-        self.registers[x as usize] = v;
-        self.registers[0xf] = if carry { 1 } else {  0 };
-        self.ip += 2;
-      },
-      [0x8, x, _, 6] => {
-        let (v, carry) = self.registers[x as usize].overflowing_shr(1);
-        self.registers[x as usize] = v;
-        self.registers[0xf] = if carry { 1 } else {  0 };
-        self.ip += 2;
-      },
-      [0x8, x, y, 7] => {
-        let (v, carry) = self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
-        self.registers[x as usize] = v;
-        self.registers[0xf] = if carry { 1 } else {  0 };
-        self.ip += 2;
-      },
-      [0x8, x, _, 0xE] => {
-        let (v, carry) = self.registers[x as usize].overflowing_shl(1);
-        self.registers[x as usize] = v;
-        self.registers[0xf] = if carry { 1 } else {  0 };
-        self.ip += 2;
-      },
-      [0x9, x, y, 0x0] => {
-        // SNE
-        self.ip += if self.registers[x as usize] != self.registers[y as usize] {
-          4
-        } else {
-          2
-        };
-      },
-      [0xA, _, _, _] => {
-        self.reg_i = rest;
-        self.ip += 2;
-      },
-      [0xB, _, _, _] => {
-        self.ip = rest + (self.registers[0] as u16);
-      },
-      [0xC, x, _, _] => {
-        self.registers[x as usize] = fastrand::u8(..) & lo;
-      },
-      [0xD, x, y, n] => {
-        println!("draw instr");
-        let bytes = &self.memory[(self.reg_i as usize)..][..(n as usize)];
-        let coords = (self.registers[x as usize] as usize, self.registers[y as usize] as usize);
-        let payload = DisplayUpdate {
-          bytes: bytes.to_vec(),
-          coords,
-        };
-        self.display.send(payload).expect("Disconnected from display!");
-        self.ip += 2;
-      },
-      [_, _, _, _] => {
-        panic!("unimplemented instruction: {:#02x}", instr);
-      },
-    };
+  if let Some(pos) = args.iter().position(|a| a == "--disasm") {
+    let rom_path = args.get(pos + 1).expect("--disasm requires <in.ch8>");
+    return disasm(rom_path);
   }
 
-  fn step(&mut self) {
-    ////self.decrement_timers();
-    self.eval_next();
-  }
+  let mut debug = false;
+  let mut quirks = Quirks::cosmac_vip();
+  let mut input = None;
 
-  fn decrement_timers(&mut self) {
-    self.timers[0] = self.timers[0].saturating_sub(1);
-    self.timers[1] = self.timers[1].saturating_sub(1);
+  let mut iter = args.iter();
+  while let Some(arg) = iter.next() {
+    match arg.as_str() {
+      "--debug" => debug = true,
+      "--quirks" => {
+        let name = iter.next().expect("--quirks requires a value");
+        quirks = Quirks::by_name(name)
+          .unwrap_or_else(|| panic!("unknown quirks profile: {} (try cosmac-vip or superchip)", name));
+      }
+      _ => input = input.or(Some(arg)),
+    }
   }
-}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-  let input = env::args().skip(1).next();
   let mut buf = vec![];
+  let rom_path: PathBuf;
 
   match input {
     None => {
-      println!("Usage: ./chippy <file.ch8>");
+      println!(
+        "Usage: ./chippy <file.ch8> [--debug] [--quirks cosmac-vip|superchip]\n   or: ./chippy --asm <in.asm> <out.ch8>\n   or: ./chippy --disasm <in.ch8>"
+      );
       return Ok(());
-    },
+    }
     Some(s) => {
-      let mut f = File::open(&s)?;
+      let mut f = File::open(s)?;
       f.read_to_end(&mut buf)?;
+      rom_path = PathBuf::from(s);
     }
   }
 
-  let (disp_tx, disp_rx) = mpsc::channel::<DisplayUpdate>();
-  let (input_tx, input_rx) = mpsc::channel::<DisplayUpdate>();
+  let (disp_tx, disp_rx) = mpsc::channel::<ScreenUpdate>();
+  let (collision_tx, collision_rx) = mpsc::channel::<bool>();
+  let (keypad_tx, keypad_rx) = mpsc::channel::<u16>();
+  let (audio_tx, audio_rx) = mpsc::channel::<bool>();
+  let (control_tx, control_rx) = mpsc::channel::<ControlMessage>();
 
   thread::spawn(move || {
-    let mut m = Machine::load(&buf, disp_tx).expect("Failed to load");
-    println!("{}", m);
-    m.run(500);
+    let mut m = Machine::load(
+      &buf,
+      disp_tx,
+      collision_rx,
+      keypad_rx,
+      audio_tx,
+      control_rx,
+      quirks,
+    )
+    .expect("Failed to load");
+    let debugger = if debug { Some(Debugger::new()) } else { None };
+    m.run(500, debugger).expect("Machine crashed");
   });
 
+  let audio_device = cpal::default_host()
+    .default_output_device()
+    .expect("No audio output device available");
+  let audio_config = audio_device.default_output_config()?;
+  let mut beeper = Some(Beeper::new(audio_rx, audio_config.sample_rate().0 as f32));
+  // Kept alive only so the stream isn't dropped once built; the output
+  // device stays closed until the ROM actually beeps (see `Beeper::poll`).
+  let mut audio_stream: Option<cpal::Stream> = None;
+
   let event_loop = EventLoop::new();
   let mut input = WinitInputHelper::new();
   let window = {
-    let size = LogicalSize::new(64.0 * 8.0, 32.0 * 8.0);
+    let size = LogicalSize::new(Screen::WIDTH as f64 * 8.0, Screen::HEIGHT as f64 * 8.0);
     WindowBuilder::new()
-      .with_title("Hello Pixels")
+      .with_title("chippy")
       .with_inner_size(size)
       .with_min_inner_size(size)
       .build(&event_loop)
@@ -297,37 +160,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
   let mut pixels = {
     let window_size = window.inner_size();
     let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-    Pixels::new(64, 32, surface_texture)?
+    Pixels::new(Screen::WIDTH as u32, Screen::HEIGHT as u32, surface_texture)?
   };
 
-  let mut screen = [[0; 64]; 32];
+  let mut screen = Screen::new();
+  let keymap = Keymap::standard();
 
   event_loop.run(move |event, _, control_flow| {
+    // Don't open the audio output device until the ROM actually beeps.
+    if let Some(b) = beeper.as_mut() {
+      if b.poll() {
+        let mut b = beeper.take().unwrap();
+        let stream = audio_device
+          .build_output_stream(
+            &audio_config.clone().into(),
+            move |data: &mut [f32], _| b.fill(data),
+            |err| eprintln!("Audio stream error: {}", err),
+            None,
+          )
+          .expect("Failed to build audio stream");
+        stream.play().expect("Failed to start audio stream");
+        audio_stream = Some(stream);
+      }
+    }
+
     // Update internal state and request a redraw
     while let Ok(msg) = disp_rx.try_recv() {
-      let (x, mut y) = msg.coords;
-      for byte in msg.bytes {
-        for i in 0..8 {
-          screen[y][x + i] ^= if byte & 1 << ((7 - i) as u8) != 0 { 1 } else { 0 };
-        }
-        y = (y + 1) % 64;
+      if let Some(collision) = screen.update(&msg) {
+        collision_tx.send(collision).expect("Machine disconnected");
       }
-    };
+    }
 
     // Draw the current frame
     if let Event::RedrawRequested(_) = event {
-      for (i, pixel) in pixels.frame_mut().chunks_exact_mut(4).enumerate() {
-        let x = (i % 64) as usize;
-        let y = (i / 64) as usize;
-
-        let color = if screen[y][x] != 0 {
-          [0xFF, 0xFF, 0xFF, 0xFF]
-        } else {
-          [0x00, 0x00, 0x00, 0xFF]
-        };
-
-        pixel.copy_from_slice(&color);
-      }
+      screen.draw(pixels.frame_mut());
 
       if pixels.render().is_err() {
         *control_flow = ControlFlow::Exit;
@@ -351,6 +217,62 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
       }
 
+      keypad_tx
+        .send(keymap.bitmask(&input))
+        .expect("Machine disconnected");
+
+      for key in [
+        VirtualKeyCode::F1,
+        VirtualKeyCode::F2,
+        VirtualKeyCode::F3,
+        VirtualKeyCode::F4,
+        VirtualKeyCode::F5,
+        VirtualKeyCode::F6,
+        VirtualKeyCode::F7,
+        VirtualKeyCode::F8,
+        VirtualKeyCode::F9,
+      ] {
+        let slot = match slot_for_key(key) {
+          Some(slot) if input.key_pressed(key) => slot,
+          _ => continue,
+        };
+
+        let (reply_tx, reply_rx) = mpsc::channel();
+        control_tx.send(ControlMessage::Save(reply_tx)).ok();
+        if let Ok(mut data) = reply_rx.recv() {
+          for row in screen.pixels() {
+            data.extend_from_slice(row);
+          }
+          if let Err(e) = save_state::save(&rom_path, slot, &data) {
+            eprintln!("Failed to save state to slot {}: {}", slot, e);
+          }
+        }
+      }
+
+      if input.key_pressed(VirtualKeyCode::F10) {
+        match save_state::load_latest(&rom_path) {
+          Ok(Some(data)) => {
+            let screen_len = Screen::WIDTH * Screen::HEIGHT;
+            if data.len() < screen_len {
+              eprintln!("Save state is too short to contain a screen snapshot");
+            } else {
+              let (machine_bytes, screen_bytes) = data.split_at(data.len() - screen_len);
+              control_tx
+                .send(ControlMessage::Load(machine_bytes.to_vec()))
+                .ok();
+
+              let mut restored = [[0u8; Screen::WIDTH]; Screen::HEIGHT];
+              for (row, chunk) in restored.iter_mut().zip(screen_bytes.chunks_exact(Screen::WIDTH)) {
+                row.copy_from_slice(chunk);
+              }
+              screen.set_pixels(restored);
+            }
+          }
+          Ok(None) => println!("No save state found for this ROM"),
+          Err(e) => eprintln!("Failed to load state: {}", e),
+        }
+      }
+
       window.request_redraw();
     }
   });
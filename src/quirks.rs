@@ -0,0 +1,53 @@
+/// Several CHIP-8 opcodes behave differently across the COSMAC VIP
+/// (the original interpreter) and later SUPER-CHIP derived ones. `Quirks`
+/// selects which behavior `Machine` emulates so a ROM written against one
+/// variant doesn't silently corrupt memory or compute the wrong shift.
+#[derive(Debug, Clone, Copy)]
+pub struct Quirks {
+  /// `SHR`/`SHL` (8xy6/8xyE): shift `Vy` into `Vx` instead of shifting `Vx`
+  /// in place. Either way `VF` is set to the bit shifted out.
+  pub shift_copies_vy: bool,
+  /// `STOR`/`LOAD` (Fx55/Fx65): increment `I` by `x + 1` afterward.
+  pub store_load_increments_i: bool,
+  /// `JPR` (Bnnn): use `Vx` (the top nibble of the address) as the offset
+  /// base instead of always `V0`.
+  pub jump_offset_uses_vx: bool,
+}
+
+impl Quirks {
+  /// The original COSMAC VIP behavior: shifts read `Vy` into `Vx`, `I`
+  /// advances after `STOR`/`LOAD`, and `JPR` always offsets from `V0`.
+  pub fn cosmac_vip() -> Self {
+    Quirks {
+      shift_copies_vy: true,
+      store_load_increments_i: true,
+      jump_offset_uses_vx: false,
+    }
+  }
+
+  /// SUPER-CHIP behavior: shifts operate on `Vx` in place, `I` is left
+  /// unchanged by `STOR`/`LOAD`, and `JPR` offsets from `Vx`.
+  pub fn superchip() -> Self {
+    Quirks {
+      shift_copies_vy: false,
+      store_load_increments_i: false,
+      jump_offset_uses_vx: true,
+    }
+  }
+
+  /// Looks up a named preset, e.g. for a `--quirks <name>` CLI flag, so
+  /// users can pick the behavior matching the ROM they're running.
+  pub fn by_name(name: &str) -> Option<Self> {
+    match name {
+      "cosmac-vip" | "cosmac_vip" => Some(Quirks::cosmac_vip()),
+      "superchip" => Some(Quirks::superchip()),
+      _ => None,
+    }
+  }
+}
+
+impl Default for Quirks {
+  fn default() -> Self {
+    Quirks::cosmac_vip()
+  }
+}
@@ -0,0 +1,47 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Number of save slots kept per ROM.
+pub const SLOT_COUNT: u32 = 9;
+
+fn slot_path(rom_path: &Path, slot: u32) -> PathBuf {
+  let name = rom_path
+    .file_name()
+    .map(|n| n.to_string_lossy().into_owned())
+    .unwrap_or_else(|| "rom".to_string());
+  rom_path.with_file_name(format!("{}.state{}", name, slot))
+}
+
+pub fn save(rom_path: &Path, slot: u32, data: &[u8]) -> io::Result<()> {
+  fs::write(slot_path(rom_path, slot), data)
+}
+
+/// Loads whichever slot for `rom_path` was written most recently, rather
+/// than assuming a fixed slot number, so "quick load" always resumes the
+/// latest save.
+pub fn load_latest(rom_path: &Path) -> io::Result<Option<Vec<u8>>> {
+  let mut newest: Option<(SystemTime, PathBuf)> = None;
+
+  for slot in 1..=SLOT_COUNT {
+    let path = slot_path(rom_path, slot);
+    let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+      Ok(modified) => modified,
+      Err(_) => continue,
+    };
+
+    let is_newer = match &newest {
+      Some((t, _)) => modified > *t,
+      None => true,
+    };
+    if is_newer {
+      newest = Some((modified, path));
+    }
+  }
+
+  match newest {
+    Some((_, path)) => Ok(Some(fs::read(path)?)),
+    None => Ok(None),
+  }
+}
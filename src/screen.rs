@@ -47,6 +47,17 @@ impl Screen {
     }
   }
 
+  /// Raw pixel state, for inclusion in a save state alongside `Machine`'s
+  /// own snapshot (the display otherwise only ever receives draws, so a
+  /// restore has nothing to repaint from without this).
+  pub fn pixels(&self) -> &[[u8; Screen::WIDTH]; Screen::HEIGHT] {
+    &self.display
+  }
+
+  pub fn set_pixels(&mut self, pixels: [[u8; Screen::WIDTH]; Screen::HEIGHT]) {
+    self.display = pixels;
+  }
+
   pub fn draw(&self, frame: &mut [u8]) {
     for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
       let x = (i % Screen::WIDTH) as usize;